@@ -18,6 +18,52 @@ impl std::convert::From<biscuit::error::Token> for PyErr {
     }
 }
 
+/// Resolves a root key passed from Python, which can be either a `PublicKey`, a callable
+/// taking the token's `root_key_id` and returning a `PublicKey`, or a dict mapping a
+/// `root_key_id` (or `None`) to a `PublicKey`
+///
+/// This is used to support root key rotation: tokens carry an optional key id, and callers
+/// can resolve it against whichever keys they currently trust
+fn resolve_root_key(root: &PyAny, key_id: Option<u32>) -> PyResult<biscuit::PublicKey> {
+    if let Ok(key) = root.extract::<PyRef<PyPublicKey>>() {
+        return Ok(key.0);
+    }
+
+    if let Ok(mapping) = root.downcast::<PyDict>() {
+        let py = root.py();
+        return match mapping.get_item(key_id.into_py(py)) {
+            Some(key) => Ok(key.extract::<PyRef<PyPublicKey>>()?.0),
+            None => Err(BiscuitValidationError::new_err(format!(
+                "no root key provided for root key id {:?}",
+                key_id
+            ))),
+        };
+    }
+
+    if root.is_callable() {
+        return Ok(root.call1((key_id,))?.extract::<PyRef<PyPublicKey>>()?.0);
+    }
+
+    Err(PyValueError::new_err(
+        "root must be a PublicKey, a callable, or a dict mapping root key ids to a PublicKey",
+    ))
+}
+
+/// Builds a `RunLimits`, overriding biscuit-rust's defaults with whichever bounds were given
+fn build_run_limits(max_facts: Option<u64>, max_iterations: Option<u64>, max_time_ms: Option<u64>) -> biscuit::datalog::RunLimits {
+    let mut limits = biscuit::datalog::RunLimits::default();
+    if let Some(max_facts) = max_facts {
+        limits.max_facts = max_facts;
+    }
+    if let Some(max_iterations) = max_iterations {
+        limits.max_iterations = max_iterations;
+    }
+    if let Some(max_time_ms) = max_time_ms {
+        limits.max_time = std::time::Duration::from_millis(max_time_ms);
+    }
+    limits
+}
+
 #[pyclass(name="BiscuitBuilder")]
 pub struct PyBiscuitBuilder {
     facts: Vec<biscuit::builder::Fact>,
@@ -120,6 +166,27 @@ impl PyBiscuit {
         }
     }
 
+    /// Creates a request to attenuate the token with a block signed by a third party
+    ///
+    /// The request can be serialized and sent to the third party, who will turn it
+    /// into a `ThirdPartyBlock` with their own keypair, to be passed to `append_third_party_block`
+    pub fn third_party_request(&self) -> PyResult<PyThirdPartyRequest> {
+        match self.0.third_party_request() {
+            Ok(request) => Ok(PyThirdPartyRequest(request)),
+            Err(error) => Err(BiscuitBuildError::new_err(error.to_string()))
+        }
+    }
+
+    /// Creates an attenuated token by adding a block signed by a third party
+    ///
+    /// `external_key` is the public key of the third party that signed the block
+    pub fn append_third_party_block(&self, external_key: &PyPublicKey, block: PyThirdPartyBlock) -> PyResult<PyBiscuit> {
+        match self.0.append_third_party(external_key.0, block.0) {
+            Ok(biscuit) => Ok(PyBiscuit(biscuit)),
+            Err(error) => Err(BiscuitBuildError::new_err(error.to_string()))
+        }
+    }
+
     /// Creates an authorizer from the token
     pub fn authorizer(&self) -> PyAuthorizer {
         PyAuthorizer {
@@ -140,23 +207,64 @@ impl PyBiscuit {
 
     /// Deserializes a token from raw data
     ///
-    /// This will check the signature using the root key
+    /// This will check the signature using the root key. `root` can be a `PublicKey`, a
+    /// callable, or a dict mapping a root key id to a `PublicKey`, to support deployments
+    /// that rotate their root key over time
     #[classmethod]
-    pub fn from_bytes(_: &PyType, data: &[u8], root: &PyPublicKey) -> PyResult<PyBiscuit> {
-        match biscuit::Biscuit::from(data, |_| root.0) {
+    pub fn from_bytes(_: &PyType, data: &[u8], root: &PyAny) -> PyResult<PyBiscuit> {
+        let key_error = std::cell::RefCell::new(None);
+        match biscuit::Biscuit::from(data, |key_id| {
+            resolve_root_key(root, key_id).map_err(|error| {
+                *key_error.borrow_mut() = Some(error);
+                biscuit::error::Format::UnknownPublicKey
+            })
+        }) {
             Ok(biscuit) => Ok(PyBiscuit(biscuit)),
-            Err(error) => Err(BiscuitValidationError::new_err(error.to_string()))
+            Err(error) => Err(key_error.into_inner().unwrap_or_else(|| BiscuitValidationError::new_err(error.to_string())))
         }
     }
 
     /// Deserializes a token from URL safe base 64 data
     ///
-    /// This will check the signature using the root key
-    /// 
+    /// This will check the signature using the root key. `root` can be a `PublicKey`, a
+    /// callable, or a dict mapping a root key id to a `PublicKey`, to support deployments
+    /// that rotate their root key over time
     #[classmethod]
-    pub fn from_base64(_: &PyType, data: &[u8], root: &PyPublicKey) -> PyResult<PyBiscuit> {
-        match biscuit::Biscuit::from_base64(data, |_| root.0) {
+    pub fn from_base64(_: &PyType, data: &[u8], root: &PyAny) -> PyResult<PyBiscuit> {
+        let key_error = std::cell::RefCell::new(None);
+        match biscuit::Biscuit::from_base64(data, |key_id| {
+            resolve_root_key(root, key_id).map_err(|error| {
+                *key_error.borrow_mut() = Some(error);
+                biscuit::error::Format::UnknownPublicKey
+            })
+        }) {
             Ok(biscuit) => Ok(PyBiscuit(biscuit)),
+            Err(error) => Err(key_error.into_inner().unwrap_or_else(|| BiscuitValidationError::new_err(error.to_string())))
+        }
+    }
+
+    /// Parses a token from raw data, without verifying its signature
+    ///
+    /// This returns an `UnverifiedBiscuit`, which exposes the token's structure (block count,
+    /// block source, revocation ids) without being usable for authorization. Call `verify` on
+    /// it, once the right root key has been picked, to get a usable `Biscuit`
+    #[classmethod]
+    pub fn from_bytes_unverified(_: &PyType, data: &[u8]) -> PyResult<PyUnverifiedBiscuit> {
+        match biscuit::UnverifiedBiscuit::from(data) {
+            Ok(biscuit) => Ok(PyUnverifiedBiscuit(biscuit)),
+            Err(error) => Err(BiscuitValidationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Parses a token from URL safe base 64 data, without verifying its signature
+    ///
+    /// This returns an `UnverifiedBiscuit`, which exposes the token's structure (block count,
+    /// block source, revocation ids) without being usable for authorization. Call `verify` on
+    /// it, once the right root key has been picked, to get a usable `Biscuit`
+    #[classmethod]
+    pub fn from_base64_unverified(_: &PyType, data: &[u8]) -> PyResult<PyUnverifiedBiscuit> {
+        match biscuit::UnverifiedBiscuit::from_base64(data) {
+            Ok(biscuit) => Ok(PyUnverifiedBiscuit(biscuit)),
             Err(error) => Err(BiscuitValidationError::new_err(error.to_string()))
         }
     }
@@ -174,7 +282,17 @@ impl PyBiscuit {
         self.0.to_base64().unwrap()
     }
 
-    // TODO Revocation IDs
+    /// Returns the revocation ids of each block, encoded as hex strings
+    ///
+    /// These can be checked against a revocation list to reject tokens that
+    /// were explicitly revoked after being issued
+    pub fn revocation_ids(&self) -> Vec<String> {
+        self.0
+            .revocation_identifiers()
+            .iter()
+            .map(hex::encode)
+            .collect()
+    }
 
     /// Returns the number of blocks in the token
     pub fn block_count(&self) -> usize {
@@ -191,6 +309,62 @@ impl PyBiscuit {
     }
 }
 
+/// A token that has been parsed but not yet had its signature verified
+///
+/// This can be obtained with `Biscuit.from_bytes_unverified` / `Biscuit.from_base64_unverified`,
+/// letting callers inspect a token's declared `root_key_id` before picking the right key to
+/// verify it with
+#[pyclass(name="UnverifiedBiscuit")]
+pub struct PyUnverifiedBiscuit(biscuit::UnverifiedBiscuit);
+
+#[pymethods]
+impl PyUnverifiedBiscuit {
+    /// Returns the number of blocks in the token
+    pub fn block_count(&self) -> usize {
+        self.0.block_count()
+    }
+
+    /// Prints a block's content as Datalog code
+    pub fn block_source(&self, index: usize) -> Option<String> {
+        self.0.print_block_source(index)
+    }
+
+    /// Returns the revocation ids of each block, encoded as hex strings
+    pub fn revocation_ids(&self) -> Vec<String> {
+        self.0
+            .revocation_identifiers()
+            .iter()
+            .map(hex::encode)
+            .collect()
+    }
+
+    /// Returns the key id the token declares it was signed with, if any
+    pub fn root_key_id(&self) -> Option<u32> {
+        self.0.root_key_id()
+    }
+
+    /// Checks the token's signature against a root key, returning a usable `Biscuit`
+    ///
+    /// `root` can be a `PublicKey`, a callable, or a dict mapping a root key id to a
+    /// `PublicKey`, to support deployments that rotate their root key over time
+    pub fn verify(&self, root: &PyAny) -> PyResult<PyBiscuit> {
+        let key_error = std::cell::RefCell::new(None);
+        match self.0.clone().check_root_key(|key_id| {
+            resolve_root_key(root, key_id).map_err(|error| {
+                *key_error.borrow_mut() = Some(error);
+                biscuit::error::Format::UnknownPublicKey
+            })
+        }) {
+            Ok(biscuit) => Ok(PyBiscuit(biscuit)),
+            Err(error) => Err(key_error.into_inner().unwrap_or_else(|| BiscuitValidationError::new_err(error.to_string())))
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.print()
+    }
+}
+
 /// The Authorizer verifies a request according to its policies and the provided token
 #[pyclass(name="Authorizer")]
 #[derive(Default)]
@@ -278,7 +452,60 @@ impl PyAuthorizer {
     ///
     /// Returns the index of the matching allow policy, or an error containing the matching deny
     /// policy or a list of the failing checks
-    pub fn authorize(&self) -> PyResult<usize> {
+    ///
+    /// `max_facts`, `max_iterations` and `max_time_ms` bound how much work the Datalog engine is
+    /// allowed to do, to protect against a pathological token running unbounded on untrusted
+    /// input. They default to biscuit-rust's own defaults when left unset
+    #[pyo3(signature = (max_facts=None, max_iterations=None, max_time_ms=None))]
+    pub fn authorize(&self, max_facts: Option<u64>, max_iterations: Option<u64>, max_time_ms: Option<u64>) -> PyResult<usize> {
+        let mut authorizer = self.build_authorizer();
+        let limits = build_run_limits(max_facts, max_iterations, max_time_ms);
+
+        match authorizer.authorize_with_limits(limits) {
+            Ok(policy_index) => Ok(policy_index),
+            Err(error) => Err(AuthorizationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Runs a Datalog rule against the authorized world, returning the matching facts
+    ///
+    /// This is typically called after `authorize()` has succeeded, to extract data the token
+    /// carried, e.g. `query("user($id) <- user($id)")` to pull out an authenticated user id
+    ///
+    /// `max_facts`, `max_iterations` and `max_time_ms` bound how much work the Datalog engine is
+    /// allowed to do, the same way they do for `authorize()`, since a query can run an
+    /// attacker-controlled rule against an untrusted token just as authorization does
+    #[pyo3(signature = (rule, max_facts=None, max_iterations=None, max_time_ms=None))]
+    pub fn query(&self, rule: &str, max_facts: Option<u64>, max_iterations: Option<u64>, max_time_ms: Option<u64>) -> PyResult<Vec<String>> {
+        let mut authorizer = self.build_authorizer();
+        let limits = build_run_limits(max_facts, max_iterations, max_time_ms);
+
+        match authorizer.query_with_limits(rule, limits) {
+            Ok(facts) => Ok(facts
+                .iter()
+                .map(|fact: &biscuit::builder::Fact| fact.to_string())
+                .collect()),
+            Err(error) => Err(AuthorizationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Prints the content of the authorizer, as seen by the Datalog engine
+    ///
+    /// This includes the token's facts (grouped by the block they come from), as well as the
+    /// facts, rules, checks and policies added to the authorizer itself, which is useful to
+    /// debug why an authorization succeeded or failed
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        self.build_authorizer().to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        self.build_authorizer().to_string()
+    }
+}
+
+impl PyAuthorizer {
+    fn build_authorizer(&self) -> biscuit::Authorizer {
         let mut authorizer = match &self.token {
             Some(token) => token
                 .authorizer().unwrap(),
@@ -301,10 +528,7 @@ impl PyAuthorizer {
                 .add_policy(policy.clone()).unwrap();
         }
 
-        match authorizer.authorize() {
-            Ok(policy_index) => Ok(policy_index),
-            Err(error) => Err(AuthorizationError::new_err(error.to_string()))
-        }
+        authorizer
     }
 }
 
@@ -350,6 +574,65 @@ impl PyBlockBuilder {
     }
 }
 
+/// A request to attenuate a token with a block signed by a third party
+#[pyclass(name="ThirdPartyRequest")]
+pub struct PyThirdPartyRequest(biscuit::ThirdPartyRequest);
+
+#[pymethods]
+impl PyThirdPartyRequest {
+    /// Serializes a third party request to raw bytes, to be sent to the third party
+    pub fn to_bytes(&self) -> PyResult<Vec<u8>> {
+        match self.0.serialize() {
+            Ok(vec) => Ok(vec),
+            Err(error) => Err(BiscuitSerializationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Deserializes a third party request from raw data
+    #[classmethod]
+    pub fn from_bytes(_: &PyType, data: &[u8]) -> PyResult<PyThirdPartyRequest> {
+        match biscuit::ThirdPartyRequest::deserialize(data) {
+            Ok(request) => Ok(PyThirdPartyRequest(request)),
+            Err(error) => Err(BiscuitValidationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Signs a third party block answering this request
+    ///
+    /// `keypair` identifies the third party, while `block_builder` carries the
+    /// facts, rules and checks they want to add to the token
+    pub fn create_block(&self, keypair: &PyKeyPair, block_builder: &PyBlockBuilder) -> PyResult<PyThirdPartyBlock> {
+        match self.0.create_block(&keypair.0, block_builder.0.clone()) {
+            Ok(block) => Ok(PyThirdPartyBlock(block)),
+            Err(error) => Err(BiscuitBuildError::new_err(error.to_string()))
+        }
+    }
+}
+
+/// A third party block, signed by a third party's keypair, ready to be appended to a token
+#[pyclass(name="ThirdPartyBlock")]
+pub struct PyThirdPartyBlock(biscuit::ThirdPartyBlock);
+
+#[pymethods]
+impl PyThirdPartyBlock {
+    /// Serializes a third party block to raw bytes, to be sent back to the token holder
+    pub fn to_bytes(&self) -> PyResult<Vec<u8>> {
+        match self.0.serialize() {
+            Ok(vec) => Ok(vec),
+            Err(error) => Err(BiscuitSerializationError::new_err(error.to_string()))
+        }
+    }
+
+    /// Deserializes a third party block from raw data
+    #[classmethod]
+    pub fn from_bytes(_: &PyType, data: &[u8]) -> PyResult<PyThirdPartyBlock> {
+        match biscuit::ThirdPartyBlock::deserialize(data) {
+            Ok(block) => Ok(PyThirdPartyBlock(block)),
+            Err(error) => Err(BiscuitValidationError::new_err(error.to_string()))
+        }
+    }
+}
+
 #[pyclass(name="KeyPair")]
 pub struct PyKeyPair(biscuit::KeyPair);
 
@@ -461,8 +744,11 @@ fn biscuit_auth(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyPublicKey>()?;
     m.add_class::<PyPrivateKey>()?;
     m.add_class::<PyBiscuit>()?;
+    m.add_class::<PyUnverifiedBiscuit>()?;
     m.add_class::<PyBiscuitBuilder>()?;
     m.add_class::<PyBlockBuilder>()?;
+    m.add_class::<PyThirdPartyRequest>()?;
+    m.add_class::<PyThirdPartyBlock>()?;
 
     m.add("DataLogError", py.get_type::<DataLogError>())?;
     m.add("AuthorizationError", py.get_type::<AuthorizationError>())?;